@@ -0,0 +1,40 @@
+use crate::error::Result;
+use crate::state::NodeAddress;
+
+/// Env var holding the cluster's node table, refreshed by the topology
+/// loop in `state.rs`. Format is a comma-separated `id=http_addr` list, e.g.
+/// `FLO_LOBBY_CLUSTER_NODES=1=http://10.0.0.1:3549,2=http://10.0.0.2:3549`.
+///
+/// This is deliberately the simplest thing that works: an env var read on
+/// every refresh tick rather than a long-lived connection to a discovery
+/// service. If the deployment grows a real service registry, swap the body
+/// of this function for a client call and leave the `ClusterConfig` /
+/// `ClusterState` plumbing in `state.rs` untouched.
+const CLUSTER_NODES_ENV: &str = "FLO_LOBBY_CLUSTER_NODES";
+
+/// Fetches the current cluster topology (node ids and HTTP addresses).
+/// Called periodically by `run_topology_refresh_loop` so a node addition or
+/// removal is picked up without restarting this process.
+pub async fn fetch_cluster_nodes() -> Result<Vec<NodeAddress>> {
+  let raw = std::env::var(CLUSTER_NODES_ENV).unwrap_or_default();
+  parse_node_table(&raw)
+}
+
+/// Parses the `id=http_addr` node table, skipping (and logging) malformed
+/// entries rather than failing the whole refresh over one bad entry.
+fn parse_node_table(raw: &str) -> Result<Vec<NodeAddress>> {
+  let mut nodes = Vec::new();
+  for entry in raw.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+    match entry.split_once('=') {
+      Some((id, addr)) => match id.parse() {
+        Ok(id) => nodes.push(NodeAddress {
+          id,
+          http_addr: addr.to_string(),
+        }),
+        Err(_) => tracing::warn!("invalid cluster node id in {:?}: {}", CLUSTER_NODES_ENV, entry),
+      },
+      None => tracing::warn!("malformed cluster node entry in {:?}: {}", CLUSTER_NODES_ENV, entry),
+    }
+  }
+  Ok(nodes)
+}
@@ -1,20 +1,282 @@
 use bs_diesel_utils::ExecutorRef;
 use parking_lot::RwLock;
-use std::collections::HashMap;
+use prometheus::{IntCounter, IntGauge, IntGaugeVec, Opts, Registry};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::{DefaultHasher, Entry};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
 use std::sync::Arc;
-use tokio::sync::{Mutex, OwnedMutexGuard};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, oneshot, Mutex, OwnedMutexGuard};
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
 
-use crate::connect::NotificationSender;
+use crate::cluster::fetch_cluster_nodes;
+use crate::connect::{Notification, NotificationSender};
 use crate::error::Result;
 use crate::game::{
-  db::{get_all_active_game_state, GameStateFromDb},
+  db::{flush_game_state_batch, get_all_active_game_state, GameStateFromDb},
   GameEntry,
 };
 
+/// Default debounce window for the write-behind flush task: membership
+/// changes within this window are coalesced into a single DB write per game.
+pub const DEFAULT_FLUSH_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How often the janitor scans for idle games and stale player state.
+pub const DEFAULT_REAP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A game with no membership activity for this long is treated as abandoned
+/// and closed.
+pub const MAX_GAME_INACTIVITY: Duration = Duration::from_secs(4 * 60 * 60);
+
+/// Player state with no connected sender for this long is dropped; the
+/// client is assumed gone rather than merely reconnecting.
+pub const MAX_CLIENT_INACTIVITY: Duration = Duration::from_secs(10 * 60);
+
+/// Capacity of a single player actor's mailbox. Notification delivery is
+/// naturally backpressured by this: a player that can't keep up stalls its
+/// own senders instead of blocking the rest of the node.
+const PLAYER_MAILBOX_SIZE: usize = 32;
+
+#[derive(Debug, Clone)]
+pub struct StorageConfig {
+  pub flush_interval: Duration,
+  pub reap_interval: Duration,
+  pub max_game_inactivity: Duration,
+  pub max_client_inactivity: Duration,
+  /// `None` runs this node standalone, owning every game locally (the
+  /// original, pre-cluster behavior).
+  pub cluster: Option<ClusterConfig>,
+}
+
+impl Default for StorageConfig {
+  fn default() -> Self {
+    StorageConfig {
+      flush_interval: DEFAULT_FLUSH_INTERVAL,
+      reap_interval: DEFAULT_REAP_INTERVAL,
+      max_game_inactivity: MAX_GAME_INACTIVITY,
+      max_client_inactivity: MAX_CLIENT_INACTIVITY,
+      cluster: None,
+    }
+  }
+}
+
+/// Identifies a node in the cluster. Stable for the node's lifetime.
+pub type NodeId = u32;
+
+/// How often the cluster topology (node list) is re-fetched.
+pub const DEFAULT_TOPOLOGY_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+const HASH_RING_REPLICAS: u32 = 128;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NodeAddress {
+  pub id: NodeId,
+  pub http_addr: String,
+}
+
+/// Enables cluster mode: games are sharded across `nodes` by consistent
+/// hashing of the game id, and `self_id` identifies which of those nodes
+/// this process is.
+#[derive(Debug, Clone)]
+pub struct ClusterConfig {
+  pub self_id: NodeId,
+  pub nodes: Vec<NodeAddress>,
+  pub topology_refresh_interval: Duration,
+}
+
+fn hash_u64<T: Hash>(value: &T) -> u64 {
+  let mut hasher = DefaultHasher::new();
+  value.hash(&mut hasher);
+  hasher.finish()
+}
+
+/// A consistent-hash ring over the node address table. Games are assigned by
+/// walking clockwise from `hash(game_id)` to the next virtual node; each
+/// physical node owns several virtual nodes so membership changes only
+/// reshuffle a fraction of the keyspace.
+#[derive(Debug, Clone, Default)]
+struct HashRing {
+  ring: BTreeMap<u64, NodeId>,
+}
+
+impl HashRing {
+  fn build(nodes: &[NodeAddress]) -> Self {
+    let mut ring = BTreeMap::new();
+    for node in nodes {
+      for replica in 0..HASH_RING_REPLICAS {
+        ring.insert(hash_u64(&(node.id, replica)), node.id);
+      }
+    }
+    HashRing { ring }
+  }
+
+  fn owner(&self, game_id: i32) -> Option<NodeId> {
+    let key = hash_u64(&game_id);
+    self
+      .ring
+      .range(key..)
+      .next()
+      .or_else(|| self.ring.iter().next())
+      .map(|(_, id)| *id)
+  }
+}
+
+/// Cluster membership plus the sticky game-id -> owning-node assignments
+/// handed out by `register_game`. The node list is refreshed periodically and
+/// rebuilds the ring, but existing assignments are kept as-is so a game's
+/// data doesn't move nodes out from under it.
+#[derive(Debug)]
+struct ClusterState {
+  self_id: NodeId,
+  nodes: HashMap<NodeId, NodeAddress>,
+  ring: HashRing,
+  game_owner: HashMap<i32, NodeId>,
+}
+
+impl ClusterState {
+  fn new(self_id: NodeId, nodes: Vec<NodeAddress>) -> Self {
+    ClusterState {
+      self_id,
+      ring: HashRing::build(&nodes),
+      nodes: nodes.into_iter().map(|n| (n.id, n)).collect(),
+      game_owner: HashMap::new(),
+    }
+  }
+
+  fn update_nodes(&mut self, nodes: Vec<NodeAddress>) {
+    self.ring = HashRing::build(&nodes);
+    self.nodes = nodes.into_iter().map(|n| (n.id, n)).collect();
+  }
+
+  /// Returns the node owning `game_id`, assigning one from the current ring
+  /// on first sight and remembering it for subsequent lookups.
+  fn assign(&mut self, game_id: i32) -> NodeId {
+    *self
+      .game_owner
+      .entry(game_id)
+      .or_insert_with(|| self.ring.owner(game_id).unwrap_or(self.self_id))
+  }
+
+  fn owner_of(&self, game_id: i32) -> Option<NodeId> {
+    self
+      .game_owner
+      .get(&game_id)
+      .copied()
+      .or_else(|| self.ring.owner(game_id))
+  }
+
+  fn address_of(&self, node_id: NodeId) -> Option<NodeAddress> {
+    self.nodes.get(&node_id).cloned()
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RemoteGameState {
+  players: Vec<i32>,
+  generation: Generation,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "action")]
+enum RemoteGameMutation {
+  AddPlayer { player_id: i32 },
+  RemovePlayer { player_id: i32 },
+  Close,
+}
+
+/// A process-wide counter minted once per game/player slot. Ids (`i32`) are
+/// reused once a game closes or a player is reaped, but a generation never
+/// is, so a `GameRef`/`PlayerRef` taken before a slot is recycled can always
+/// be told apart from one taken after.
+pub type Generation = u64;
+
+/// A game id plus the generation of the slot it was registered into. Handed
+/// out by `register_game` and checked by `lock_game_state`: if the id has
+/// since been closed and reused by a different game, the generation won't
+/// match and the stale ref is rejected instead of silently addressing the
+/// new occupant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct GameRef {
+  pub id: i32,
+  pub generation: Generation,
+}
+
+/// A player id plus the generation of the actor slot it was spawned into.
+/// The player-side counterpart of `GameRef`; see `StorageHandle::player_ref`
+/// and `StorageHandle::lock_player_state`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PlayerRef {
+  pub id: i32,
+  pub generation: Generation,
+}
+
+/// Gauges/counters tracking the node's live game and player state. The
+/// per-game player gauge is set straight from `game_num_players` at every
+/// mutation site, so it and `fetch_num_players` can never drift apart. The
+/// `active_games` / `active_players` gauges are seeded from the loaded maps
+/// and then incremented/decremented where games and player entries are
+/// created, closed or reaped.
+#[derive(Debug, Clone)]
+struct StorageMetrics {
+  active_games: IntGauge,
+  active_players: IntGauge,
+  game_players: IntGaugeVec,
+  games_closed_total: IntCounter,
+}
+
+impl StorageMetrics {
+  fn register(registry: &Registry) -> Result<Self> {
+    let active_games = IntGauge::new(
+      "flo_lobby_active_games",
+      "Number of active games tracked by this node",
+    )?;
+    let active_players = IntGauge::new(
+      "flo_lobby_active_players",
+      "Number of active player-state entries tracked by this node",
+    )?;
+    let game_players = IntGaugeVec::new(
+      Opts::new("flo_lobby_game_players", "Number of players in each active game"),
+      &["game_id"],
+    )?;
+    let games_closed_total = IntCounter::new(
+      "flo_lobby_games_closed_total",
+      "Total number of games closed",
+    )?;
+
+    registry.register(Box::new(active_games.clone()))?;
+    registry.register(Box::new(active_players.clone()))?;
+    registry.register(Box::new(game_players.clone()))?;
+    registry.register(Box::new(games_closed_total.clone()))?;
+
+    Ok(StorageMetrics {
+      active_games,
+      active_players,
+      game_players,
+      games_closed_total,
+    })
+  }
+
+  fn set_game_players(&self, game_id: i32, count: usize) {
+    self
+      .game_players
+      .with_label_values(&[&game_id.to_string()])
+      .set(count as i64);
+  }
+
+  fn game_closed(&self, game_id: i32) {
+    self.active_games.dec();
+    self.games_closed_total.inc();
+    let _ = self.game_players.remove_label_values(&[&game_id.to_string()]);
+  }
+}
+
 #[derive(Debug)]
 pub struct GameState {
   pub players: Vec<i32>,
   closed: bool,
+  last_activity: Instant,
 }
 
 impl GameState {
@@ -22,74 +284,613 @@ impl GameState {
     GameState {
       players: players.to_vec(),
       closed: false,
+      last_activity: Instant::now(),
+    }
+  }
+}
+
+/// A game's mutex-guarded state plus the generation it was registered
+/// under. Kept separate from `GameState` itself so the generation is
+/// visible (for the id-reuse check in `lock_local_game_state`) without
+/// taking the per-game lock.
+#[derive(Debug, Clone)]
+struct GameSlot {
+  generation: Generation,
+  state: Arc<Mutex<GameState>>,
+}
+
+#[derive(Debug)]
+struct PlayerState {
+  game_id: Option<i32>,
+  sender: Option<NotificationSender>,
+  last_activity: Instant,
+}
+
+impl PlayerState {
+  fn new(game_id: Option<i32>) -> Self {
+    PlayerState {
+      game_id,
+      sender: None,
+      last_activity: Instant::now(),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PlayerStatus {
+  has_sender: bool,
+  last_activity: Instant,
+}
+
+/// Commands accepted by a player actor's mailbox. Every mutation and read of
+/// a player's state goes through here, so the actor processes them one at a
+/// time without any lock: there is nothing to contend over.
+#[derive(Debug)]
+enum PlayerCommand {
+  JoinGame {
+    game_id: i32,
+  },
+  LeaveGame,
+  /// Atomically replaces the player's current game assignment and reports
+  /// back whichever one it held before. A single mailbox round trip, unlike
+  /// a caller reading `QueryGameId` and then separately sending `JoinGame`/
+  /// `LeaveGame`, which leaves a window for another request for the same
+  /// player to interleave its own read and write in between.
+  SwitchGame {
+    game_id: Option<i32>,
+    reply: oneshot::Sender<Option<i32>>,
+  },
+  SetSender {
+    sender: Option<NotificationSender>,
+  },
+  SendNotification {
+    notification: Notification,
+  },
+  QueryGameId {
+    reply: oneshot::Sender<Option<i32>>,
+  },
+  QueryStatus {
+    reply: oneshot::Sender<PlayerStatus>,
+  },
+  /// Atomically re-checks idleness against the actor's *current* state and,
+  /// if it's still idle, tears the actor down in the same mailbox step.
+  /// Unlike a `status()` read followed by a separate `shutdown()`, nothing
+  /// can be enqueued in between that this would silently discard: a
+  /// reconnect (`SetSender`/`JoinGame`) queued ahead of this command is
+  /// processed first and flips `has_sender`/`last_activity` before this
+  /// check runs, so the actor survives instead of being torn down under it.
+  ReapIfIdle {
+    max_client_inactivity: Duration,
+    reply: oneshot::Sender<bool>,
+  },
+  Shutdown,
+}
+
+/// A handle to a player's actor task. Cheap to clone; cloning just clones the
+/// mailbox sender.
+#[derive(Debug, Clone)]
+struct PlayerHandle {
+  tx: mpsc::Sender<PlayerCommand>,
+}
+
+impl PlayerHandle {
+  fn spawn(id: i32, game_id: Option<i32>) -> Self {
+    let (tx, rx) = mpsc::channel(PLAYER_MAILBOX_SIZE);
+    tokio::spawn(run_player_actor(id, game_id, rx));
+    PlayerHandle { tx }
+  }
+
+  async fn join_game(&self, game_id: i32) {
+    let _ = self.tx.send(PlayerCommand::JoinGame { game_id }).await;
+  }
+
+  async fn leave_game(&self) {
+    let _ = self.tx.send(PlayerCommand::LeaveGame).await;
+  }
+
+  /// Atomically swaps in `game_id` and returns whichever game was previously
+  /// assigned (`None` if the swap command never reaches the actor, same as a
+  /// dropped `JoinGame`/`LeaveGame`).
+  async fn switch_game(&self, game_id: Option<i32>) -> Option<i32> {
+    let (reply, rx) = oneshot::channel();
+    if self
+      .tx
+      .send(PlayerCommand::SwitchGame { game_id, reply })
+      .await
+      .is_err()
+    {
+      return None;
+    }
+    rx.await.unwrap_or(None)
+  }
+
+  async fn set_sender(&self, sender: Option<NotificationSender>) {
+    let _ = self.tx.send(PlayerCommand::SetSender { sender }).await;
+  }
+
+  async fn send_notification(&self, notification: Notification) {
+    let _ = self
+      .tx
+      .send(PlayerCommand::SendNotification { notification })
+      .await;
+  }
+
+  async fn game_id(&self) -> Option<i32> {
+    let (reply, rx) = oneshot::channel();
+    if self.tx.send(PlayerCommand::QueryGameId { reply }).await.is_err() {
+      return None;
+    }
+    rx.await.unwrap_or(None)
+  }
+
+  async fn status(&self) -> Option<PlayerStatus> {
+    let (reply, rx) = oneshot::channel();
+    if self.tx.send(PlayerCommand::QueryStatus { reply }).await.is_err() {
+      return None;
+    }
+    rx.await.ok()
+  }
+
+  /// See `PlayerCommand::ReapIfIdle`. Returns whether the actor reaped
+  /// itself (also `true` if the actor was already gone by the time this
+  /// sent, since there's nothing left for a caller to clean up either way).
+  async fn reap_if_idle(&self, max_client_inactivity: Duration) -> bool {
+    let (reply, rx) = oneshot::channel();
+    if self
+      .tx
+      .send(PlayerCommand::ReapIfIdle {
+        max_client_inactivity,
+        reply,
+      })
+      .await
+      .is_err()
+    {
+      return true;
     }
+    rx.await.unwrap_or(true)
+  }
+
+  async fn shutdown(&self) {
+    let _ = self.tx.send(PlayerCommand::Shutdown).await;
+  }
+
+  /// Whether the actor behind this handle has already terminated (reaped
+  /// itself via `ReapIfIdle`/`Shutdown`, or panicked). A `player_ref` lookup
+  /// that finds this true for the slot it's about to hand back must respawn
+  /// instead, or the caller's command silently vanishes into a dead mailbox.
+  fn is_closed(&self) -> bool {
+    self.tx.is_closed()
   }
 }
 
-#[derive(Debug, Default)]
-pub struct PlayerState {
-  pub game_id: Option<i32>,
-  pub sender: Option<NotificationSender>,
+/// A player actor's mailbox handle plus the generation it was spawned
+/// under. See `GameSlot` for why the generation rides alongside the id
+/// instead of being tracked separately.
+#[derive(Debug, Clone)]
+struct PlayerSlot {
+  generation: Generation,
+  handle: PlayerHandle,
+}
+
+async fn run_player_actor(id: i32, game_id: Option<i32>, mut rx: mpsc::Receiver<PlayerCommand>) {
+  let mut state = PlayerState::new(game_id);
+
+  while let Some(cmd) = rx.recv().await {
+    match cmd {
+      PlayerCommand::JoinGame { game_id } => {
+        state.game_id = Some(game_id);
+        state.last_activity = Instant::now();
+      }
+      PlayerCommand::LeaveGame => {
+        state.game_id = None;
+        state.last_activity = Instant::now();
+      }
+      PlayerCommand::SwitchGame { game_id, reply } => {
+        let previous = std::mem::replace(&mut state.game_id, game_id);
+        state.last_activity = Instant::now();
+        let _ = reply.send(previous);
+      }
+      PlayerCommand::SetSender { sender } => {
+        state.sender = sender;
+        state.last_activity = Instant::now();
+      }
+      PlayerCommand::SendNotification { notification } => {
+        state.last_activity = Instant::now();
+        if let Some(sender) = &state.sender {
+          if let Err(err) = sender.send(notification) {
+            tracing::warn!("send notification to player {}: {}", id, err);
+          }
+        }
+      }
+      PlayerCommand::QueryGameId { reply } => {
+        let _ = reply.send(state.game_id);
+      }
+      PlayerCommand::QueryStatus { reply } => {
+        let _ = reply.send(PlayerStatus {
+          has_sender: state.sender.is_some(),
+          last_activity: state.last_activity,
+        });
+      }
+      PlayerCommand::ReapIfIdle {
+        max_client_inactivity,
+        reply,
+      } => {
+        let idle = state.sender.is_none()
+          && Instant::now().duration_since(state.last_activity) >= max_client_inactivity;
+        let _ = reply.send(idle);
+        if idle {
+          break;
+        }
+      }
+      PlayerCommand::Shutdown => break,
+    }
+  }
 }
 
 #[derive(Debug)]
 pub struct Storage {
   state: Arc<RwLock<StorageState>>,
+  flush_tx: mpsc::Sender<oneshot::Sender<()>>,
+  flush_task: JoinHandle<()>,
+  janitor_task: JoinHandle<()>,
+  topology_task: Option<JoinHandle<()>>,
 }
 
 impl Storage {
-  pub async fn init(db: ExecutorRef) -> Result<Self> {
+  pub async fn init(db: ExecutorRef, registry: &Registry) -> Result<Self> {
+    Self::init_with_config(db, registry, StorageConfig::default()).await
+  }
+
+  pub async fn init_with_config(
+    db: ExecutorRef,
+    registry: &Registry,
+    config: StorageConfig,
+  ) -> Result<Self> {
     let data = db.exec(|conn| get_all_active_game_state(conn)).await?;
+    let metrics = StorageMetrics::register(registry)?;
+    let cluster = config
+      .cluster
+      .as_ref()
+      .map(|c| ClusterState::new(c.self_id, c.nodes.clone()));
+    let state = Arc::new(RwLock::new(StorageState::new(data, metrics, cluster)));
+
+    let (flush_tx, flush_rx) = mpsc::channel(1);
+    let flush_task = tokio::spawn(run_flush_loop(
+      state.clone(),
+      db,
+      config.flush_interval,
+      flush_rx,
+    ));
+    let janitor_task = tokio::spawn(run_janitor_loop(state.clone(), config.clone()));
+    let topology_task = config.cluster.as_ref().map(|cluster_config| {
+      tokio::spawn(run_topology_refresh_loop(
+        state.clone(),
+        cluster_config.topology_refresh_interval,
+      ))
+    });
 
     Ok(Storage {
-      state: Arc::new(RwLock::new(StorageState::new(data))),
+      state,
+      flush_tx,
+      flush_task,
+      janitor_task,
+      topology_task,
     })
   }
 
   pub fn handle(&self) -> StorageHandle {
     StorageHandle(self.state.clone())
   }
+
+  /// Forces an immediate flush of all dirty games, bypassing the debounce
+  /// window. `shutdown` calls this so no membership update is left
+  /// unpersisted.
+  pub async fn flush_now(&self) {
+    let (tx, rx) = oneshot::channel();
+    if self.flush_tx.send(tx).await.is_ok() {
+      let _ = rx.await;
+    }
+  }
+
+  pub async fn shutdown(self) {
+    self.flush_now().await;
+    self.flush_task.abort();
+    self.janitor_task.abort();
+    if let Some(task) = self.topology_task {
+      task.abort();
+    }
+  }
+}
+
+async fn run_topology_refresh_loop(state: Arc<RwLock<StorageState>>, interval: Duration) {
+  let mut ticker = tokio::time::interval(interval);
+  ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+  loop {
+    ticker.tick().await;
+    match fetch_cluster_nodes().await {
+      Ok(nodes) => {
+        let mut s = state.write();
+        if let Some(cluster) = s.cluster.as_mut() {
+          cluster.update_nodes(nodes);
+        }
+      }
+      Err(err) => tracing::warn!("refresh cluster topology: {}", err),
+    }
+  }
+}
+
+async fn run_flush_loop(
+  state: Arc<RwLock<StorageState>>,
+  db: ExecutorRef,
+  flush_interval: Duration,
+  mut flush_rx: mpsc::Receiver<oneshot::Sender<()>>,
+) {
+  let mut ticker = tokio::time::interval(flush_interval);
+  ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+  loop {
+    let ack = tokio::select! {
+      _ = ticker.tick() => None,
+      ack = flush_rx.recv() => match ack {
+        Some(ack) => Some(ack),
+        None => break,
+      },
+    };
+
+    if let Err(err) = flush_dirty(&state, &db).await {
+      tracing::error!("flush dirty game state: {}", err);
+    }
+
+    if let Some(ack) = ack {
+      let _ = ack.send(());
+    }
+  }
+}
+
+async fn flush_dirty(state: &Arc<RwLock<StorageState>>, db: &ExecutorRef) -> Result<()> {
+  let (dirty_ids, pending): (Vec<i32>, Vec<(i32, Vec<i32>, bool)>) = {
+    let mut s = state.write();
+    (s.dirty.drain().collect(), s.pending_flush.drain(..).collect())
+  };
+
+  let games: Vec<(i32, Arc<Mutex<GameState>>)> = {
+    let s = state.read();
+    dirty_ids
+      .iter()
+      .copied()
+      // Defense in depth: only ever flush games this node owns. In steady
+      // state `games`/`dirty` shouldn't hold foreign ids at all (see
+      // `StorageState::new` and `register_game`), but this keeps a stale or
+      // racing entry from overwriting a remote node's copy of a game it's
+      // actively serving.
+      .filter(|id| s.owns(*id))
+      .filter_map(|id| s.games.get(&id).map(|slot| (id, slot.state.clone())))
+      .collect()
+  };
+
+  if games.is_empty() && pending.is_empty() {
+    return Ok(());
+  }
+
+  let mut batch = pending.clone();
+  for (id, game) in games {
+    let guard = game.lock().await;
+    batch.push((id, guard.players.clone(), guard.closed));
+  }
+
+  if let Err(err) = db.exec(move |conn| flush_game_state_batch(conn, &batch)).await {
+    // The write never landed, so undo the drain: put the ids back in
+    // `dirty` (the next tick will re-lock whichever games are still live
+    // and build a fresh batch for them) and the evicted-game snapshots
+    // back in `pending_flush` verbatim, since nothing else remembers them.
+    // Otherwise a single transient DB error would silently and
+    // permanently drop every membership change this flush batched up.
+    let mut s = state.write();
+    s.dirty.extend(dirty_ids);
+    s.pending_flush.extend(pending);
+    return Err(err.into());
+  }
+
+  Ok(())
+}
+
+async fn run_janitor_loop(state: Arc<RwLock<StorageState>>, config: StorageConfig) {
+  let mut ticker = tokio::time::interval(config.reap_interval);
+  ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+  loop {
+    ticker.tick().await;
+    reap_inactive(&state, &config).await;
+  }
+}
+
+/// Scans for idle games and stale player state. Only holds the global lock
+/// long enough to snapshot the candidate ids; each game mutex / player
+/// mailbox is then addressed individually so a slow flush/notification
+/// elsewhere never blocks on the janitor (or vice versa).
+async fn reap_inactive(state: &Arc<RwLock<StorageState>>, config: &StorageConfig) {
+  let (games, players): (Vec<(i32, Arc<Mutex<GameState>>)>, Vec<(PlayerRef, PlayerHandle)>) = {
+    let s = state.read();
+    (
+      // Same defense in depth as `flush_dirty`: never let this node's
+      // janitor close (and dirty) a game it doesn't own.
+      s.games
+        .iter()
+        .filter(|(id, _)| s.owns(**id))
+        .map(|(id, slot)| (*id, slot.state.clone()))
+        .collect(),
+      s.players
+        .iter()
+        .map(|(id, slot)| {
+          (
+            PlayerRef {
+              id: *id,
+              generation: slot.generation,
+            },
+            slot.handle.clone(),
+          )
+        })
+        .collect(),
+    )
+  };
+
+  let now = Instant::now();
+
+  for (id, game) in games {
+    let mut guard = game.lock().await;
+    if !guard.closed && now.duration_since(guard.last_activity) >= config.max_game_inactivity {
+      tracing::info!("reaping idle game: id = {}", id);
+      guard.closed = true;
+      drop(guard);
+      let mut s = state.write();
+      s.game_num_players.remove(&id);
+      s.dirty.insert(id);
+      s.metrics.game_closed(id);
+    }
+  }
+
+  let mut reaped_count = 0;
+  for (player_ref, handle) in players {
+    // `status()` is a cheap pre-filter over a point-in-time snapshot, so it
+    // can race a reconnect that lands right after. `reap_if_idle` below is
+    // what actually decides and acts, atomically against the actor's state
+    // at the moment it's processed, so that race can't drop a connection.
+    if let Some(status) = handle.status().await {
+      if !status.has_sender
+        && now.duration_since(status.last_activity) >= config.max_client_inactivity
+        && handle.reap_if_idle(config.max_client_inactivity).await
+      {
+        // The actor is already gone the moment `reap_if_idle` returns
+        // true, but the slot it leaves behind could have already been
+        // respawned in place by `player_ref` for a reconnecting client (see
+        // its `is_closed` check) before this write lock is taken.
+        // Revalidate the generation we snapshotted against the live slot
+        // and only remove it on a match, so a fresh respawn that raced us
+        // here is left alone instead of being evicted out from under it.
+        let mut s = state.write();
+        if s
+          .players
+          .get(&player_ref.id)
+          .is_some_and(|slot| slot.generation == player_ref.generation)
+        {
+          s.players.remove(&player_ref.id);
+          reaped_count += 1;
+        }
+      }
+    }
+  }
+
+  if reaped_count > 0 {
+    state.write().metrics.active_players.sub(reaped_count);
+    tracing::info!("reaped stale player state: count = {}", reaped_count);
+  }
 }
 
 #[derive(Debug)]
 struct StorageState {
-  players: HashMap<i32, Arc<Mutex<PlayerState>>>,
-  games: HashMap<i32, Arc<Mutex<GameState>>>,
+  players: HashMap<i32, PlayerSlot>,
+  games: HashMap<i32, GameSlot>,
   game_num_players: HashMap<i32, usize>,
+  dirty: HashSet<i32>,
+  /// Final `(id, players, closed)` snapshots for games evicted from `games`
+  /// while still dirty (see `lock_local_game_state`), so the debounced flush
+  /// loop still persists them once their slot is gone and a plain `dirty`
+  /// id lookup into `games` would otherwise find nothing.
+  pending_flush: Vec<(i32, Vec<i32>, bool)>,
+  metrics: StorageMetrics,
+  cluster: Option<ClusterState>,
+  http: reqwest::Client,
+  /// Next generation to hand out to a newly created or recycled game/player
+  /// slot. Shared across both maps since they index disjoint id spaces;
+  /// never reset, so a recycled id always gets a generation its
+  /// predecessor never had, even after the predecessor's slot is removed.
+  next_generation: Generation,
 }
 
 impl StorageState {
-  fn new(data: Vec<GameStateFromDb>) -> Self {
+  fn new(
+    data: Vec<GameStateFromDb>,
+    metrics: StorageMetrics,
+    mut cluster: Option<ClusterState>,
+  ) -> Self {
     let mut players = HashMap::new();
     let mut games = HashMap::new();
     let mut game_num_players = HashMap::new();
+    let mut next_generation: Generation = 0;
 
     for item in data {
+      // In cluster mode, only load the shard of games this node actually
+      // owns: otherwise every node would replicate the whole deployment's
+      // game/player state and spawn an actor per player system-wide, which
+      // is the unbounded-memory problem sharding exists to avoid. `assign`
+      // also seeds `game_owner` so `owns`/`owner_of` agree with what got
+      // loaded here regardless of ring churn later.
+      if let Some(cluster) = cluster.as_mut() {
+        if cluster.assign(item.id) != cluster.self_id {
+          continue;
+        }
+      }
+
       for player_id in &item.players {
+        let generation = next_generation;
+        next_generation += 1;
         players.insert(
           *player_id,
-          Arc::new(Mutex::new(PlayerState {
-            game_id: Some(item.id),
-            sender: None,
-          })),
+          PlayerSlot {
+            generation,
+            handle: PlayerHandle::spawn(*player_id, Some(item.id)),
+          },
         );
       }
 
       game_num_players.insert(item.id, item.players.len());
+      metrics.set_game_players(item.id, item.players.len());
 
+      let generation = next_generation;
+      next_generation += 1;
       games.insert(
         item.id,
-        Arc::new(Mutex::new(GameState {
-          players: item.players,
-          closed: false,
-        })),
+        GameSlot {
+          generation,
+          state: Arc::new(Mutex::new(GameState {
+            players: item.players,
+            closed: false,
+            last_activity: Instant::now(),
+          })),
+        },
       );
     }
 
+    metrics.active_games.set(games.len() as i64);
+    metrics.active_players.set(players.len() as i64);
+
     Self {
       players,
       games,
       game_num_players,
+      dirty: HashSet::new(),
+      pending_flush: Vec::new(),
+      metrics,
+      cluster,
+      http: reqwest::Client::new(),
+      next_generation,
+    }
+  }
+
+  /// Mints the next generation for a newly created or recycled slot.
+  fn next_generation(&mut self) -> Generation {
+    let generation = self.next_generation;
+    self.next_generation += 1;
+    generation
+  }
+
+  /// `true` when this node owns `game_id` locally, i.e. cluster mode is off
+  /// or the ring/sticky assignment maps it to `self_id`.
+  fn owns(&self, game_id: i32) -> bool {
+    match &self.cluster {
+      Some(cluster) => cluster.owner_of(game_id) == Some(cluster.self_id),
+      None => true,
     }
   }
 }
@@ -98,47 +899,312 @@ impl StorageState {
 pub struct StorageHandle(Arc<RwLock<StorageState>>);
 
 impl StorageHandle {
-  pub async fn register_game(&self, id: i32, players: &[i32]) {
+  /// Registers a new game, placing it on whichever node the cluster's hash
+  /// ring selects for `id`. When that's a remote node, the roster is handed
+  /// off via RPC instead of being stored locally. Returns the `GameRef` the
+  /// caller should hold onto and pass back to `lock_game_state`; it pins
+  /// down this exact registration even if `id` is later closed and reused.
+  pub async fn register_game(&self, id: i32, players: &[i32]) -> Result<GameRef> {
+    let remote = {
+      let mut storage_lock = self.0.write();
+      match storage_lock.cluster.as_mut() {
+        Some(cluster) => {
+          let owner = cluster.assign(id);
+          if owner == cluster.self_id {
+            None
+          } else {
+            match cluster.address_of(owner) {
+              Some(addr) => Some(Some((addr, storage_lock.http.clone()))),
+              // `game_owner` is a sticky assignment that's never invalidated
+              // when a node drops out of `update_nodes` (see `ClusterState`),
+              // so this id can point at a node that no longer exists. Falling
+              // through to the local-handling path below would create a
+              // `GameSlot` this node doesn't actually own (`owns(id)` still
+              // says otherwise), leaking a ghost entry that `flush_dirty`/
+              // `reap_inactive` can never reach. Surface it as an error
+              // instead.
+              None => Some(None),
+            }
+          }
+        }
+        None => None,
+      }
+    };
+
+    match remote {
+      Some(Some((addr, http))) => {
+        let url = format!("{}/cluster/games/{}/register", addr.http_addr, id);
+        // `generation` is unused by the register endpoint (the owning node
+        // mints it); only `players` is read from the request body.
+        let body = RemoteGameState {
+          players: players.to_vec(),
+          generation: 0,
+        };
+        let remote: RemoteGameState = http
+          .post(&url)
+          .json(&body)
+          .send()
+          .await?
+          .error_for_status()?
+          .json()
+          .await?;
+        return Ok(GameRef {
+          id,
+          generation: remote.generation,
+        });
+      }
+      Some(None) => {
+        return Err(anyhow::anyhow!(
+          "game {} is sticky-assigned to a node with no known address",
+          id
+        ));
+      }
+      None => {}
+    }
+
     let mut storage_lock = self.0.write();
-    if storage_lock.games.contains_key(&id) {
+    let is_new = !storage_lock.games.contains_key(&id);
+    if !is_new {
       tracing::warn!("override game state: id = {}", id);
     }
+    let generation = storage_lock.next_generation();
     storage_lock.game_num_players.insert(id, players.len());
-    storage_lock
-      .games
-      .insert(id, Arc::new(Mutex::new(GameState::new(players))));
+    storage_lock.games.insert(
+      id,
+      GameSlot {
+        generation,
+        state: Arc::new(Mutex::new(GameState::new(players))),
+      },
+    );
+    storage_lock.dirty.insert(id);
+    storage_lock.metrics.set_game_players(id, players.len());
+    if is_new {
+      storage_lock.metrics.active_games.inc();
+    }
+    for player_id in players {
+      if !storage_lock.players.contains_key(player_id) {
+        let player_generation = storage_lock.next_generation();
+        storage_lock.players.insert(
+          *player_id,
+          PlayerSlot {
+            generation: player_generation,
+            handle: PlayerHandle::spawn(*player_id, Some(id)),
+          },
+        );
+        storage_lock.metrics.active_players.inc();
+      }
+    }
+    Ok(GameRef { id, generation })
   }
 
-  pub async fn lock_player_state(&self, id: i32) -> LockedPlayerState {
-    let state: Arc<Mutex<_>> = {
-      let mut storage_lock = self.0.write();
-      storage_lock
-        .players
-        .entry(id)
-        .or_insert_with(|| Arc::new(Mutex::new(PlayerState::default())))
-        .clone()
+  fn player_handle(&self, id: i32) -> PlayerHandle {
+    self.player_ref(id).1
+  }
+
+  /// Returns a generation-tagged reference to player `id`'s actor, spawning
+  /// it on first touch. Unlike the convenience methods below (which address
+  /// the mailbox immediately and never outlive a single `.await`), a caller
+  /// that holds onto a `PlayerRef` across other work should revalidate it
+  /// with `lock_player_state` before acting on it, in case the player was
+  /// reaped and `id` reused in the meantime. `reap_inactive` is exactly such
+  /// a caller: it holds a `PlayerRef` from its initial scan across the
+  /// `status`/`reap_if_idle` mailbox round trips and revalidates the
+  /// generation immediately before removing the slot.
+  pub fn player_ref(&self, id: i32) -> (PlayerRef, PlayerHandle) {
+    let mut storage_lock = self.0.write();
+    let storage = &mut *storage_lock;
+    let slot = match storage.players.entry(id) {
+      Entry::Occupied(entry) => {
+        let slot = entry.into_mut();
+        if slot.handle.is_closed() {
+          // The janitor already reaped this id's actor but hasn't (or
+          // couldn't) clean up the slot yet; handing back the dead handle
+          // would silently swallow whatever this caller sends. Mint a
+          // fresh generation and respawn in place, same as a vacant entry
+          // -- but the slot itself isn't new, so `active_players` (already
+          // counting it) doesn't get bumped again.
+          let generation = storage.next_generation;
+          storage.next_generation += 1;
+          *slot = PlayerSlot {
+            generation,
+            handle: PlayerHandle::spawn(id, None),
+          };
+        }
+        slot
+      }
+      Entry::Vacant(entry) => {
+        storage.metrics.active_players.inc();
+        let generation = storage.next_generation;
+        storage.next_generation += 1;
+        entry.insert(PlayerSlot {
+          generation,
+          handle: PlayerHandle::spawn(id, None),
+        })
+      }
     };
-    LockedPlayerState {
-      id,
-      guard: state.lock_owned().await,
+    (
+      PlayerRef {
+        id,
+        generation: slot.generation,
+      },
+      slot.handle.clone(),
+    )
+  }
+
+  /// Validates `player_ref` against the current slot generation and returns
+  /// the actor handle on a match, or `None` if `id` was reaped (and
+  /// possibly respawned for an unrelated connection) since the ref was
+  /// taken.
+  pub fn lock_player_state(&self, player_ref: PlayerRef) -> Option<PlayerHandle> {
+    let storage_lock = self.0.read();
+    storage_lock.players.get(&player_ref.id).and_then(|slot| {
+      if slot.generation == player_ref.generation {
+        Some(slot.handle.clone())
+      } else {
+        None
+      }
+    })
+  }
+
+  /// Sets the player's game assignment outright. For a caller that needs to
+  /// act on whatever game the player was previously in (e.g. remove them
+  /// from its roster before seating them in a new one), use `switch_game`
+  /// instead: reading `player_game_id` here and then calling `join_game`
+  /// separately is two mailbox round trips, and a concurrent request for the
+  /// same player can interleave its own read and write in between.
+  pub async fn join_game(&self, player_id: i32, game_id: i32) {
+    self.player_handle(player_id).join_game(game_id).await;
+  }
+
+  /// See the `join_game` note: prefer `switch_game` over this when the
+  /// caller also needs to know (and act on) the game being left.
+  pub async fn leave_game(&self, player_id: i32) {
+    self.player_handle(player_id).leave_game().await;
+  }
+
+  /// Atomically replaces the player's game assignment with `game_id` and
+  /// returns whichever game they previously held, if any, as a single
+  /// mailbox round trip. Use this instead of `player_game_id` followed by
+  /// `join_game`/`leave_game` whenever the caller needs to act on the old
+  /// assignment (e.g. drop the player from their previous game's roster)
+  /// without a window for another request for the same player to race it.
+  pub async fn switch_game(&self, player_id: i32, game_id: Option<i32>) -> Option<i32> {
+    self.player_handle(player_id).switch_game(game_id).await
+  }
+
+  pub async fn set_player_sender(&self, player_id: i32, sender: Option<NotificationSender>) {
+    self.player_handle(player_id).set_sender(sender).await;
+  }
+
+  pub async fn send_player_notification(&self, player_id: i32, notification: Notification) {
+    self
+      .player_handle(player_id)
+      .send_notification(notification)
+      .await;
+  }
+
+  pub async fn player_game_id(&self, player_id: i32) -> Option<i32> {
+    self.player_handle(player_id).game_id().await
+  }
+
+  /// Locks the game identified by `game_ref`, whether it's owned by this
+  /// node or a remote one. Local ownership is checked first so the common
+  /// single-node (or "it's mine") case never pays for an RPC round-trip.
+  /// Returns `None` if `game_ref`'s id was since closed, or closed and
+  /// reused by a different game (the generation mismatches).
+  pub async fn lock_game_state(&self, game_ref: GameRef) -> Result<Option<GameHandle>> {
+    let remote_owner = {
+      let s = self.0.read();
+      if s.owns(game_ref.id) {
+        None
+      } else {
+        s.cluster.as_ref().and_then(|c| c.owner_of(game_ref.id)).map(|owner| {
+          s.cluster
+            .as_ref()
+            .and_then(|c| c.address_of(owner))
+            .map(|addr| (addr, s.http.clone()))
+        })
+      }
+    };
+
+    let remote_owner = match remote_owner {
+      // Not locally owned, and the owning node's address is unresolved: the
+      // sticky `game_owner` entry (never invalidated on node removal, see
+      // `ClusterState::update_nodes`) outlived the node. Treat this as a
+      // retryable error rather than silently falling through to the local
+      // path, which would wrongly report a remote game as nonexistent.
+      Some(None) => {
+        return Err(anyhow::anyhow!(
+          "game {} is owned by a node with no known address",
+          game_ref.id
+        ));
+      }
+      Some(Some(target)) => Some(target),
+      None => None,
+    };
+
+    if let Some((addr, http)) = remote_owner {
+      let url = format!(
+        "{}/cluster/games/{}/lock?generation={}",
+        addr.http_addr, game_ref.id, game_ref.generation
+      );
+      let resp = http.post(&url).send().await?;
+      if resp.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+      }
+      let remote: RemoteGameState = resp.error_for_status()?.json().await?;
+      return Ok(Some(GameHandle::Remote(RemoteGameHandle {
+        game_ref,
+        owner: addr,
+        http,
+        players: remote.players,
+      })));
     }
+
+    Ok(self.lock_local_game_state(game_ref).await.map(GameHandle::Local))
   }
 
-  pub async fn lock_game_state(&self, id: i32) -> Option<LockedGameState> {
+  /// The original single-node lock path; also used to serve the cluster RPC
+  /// endpoint when a remote node asks this one for a game it owns. Returns
+  /// `None` both when `game_ref.id` has no slot and when it has one under a
+  /// different generation (the id was recycled out from under the ref).
+  pub async fn lock_local_game_state(&self, game_ref: GameRef) -> Option<LockedGameState> {
     let state = {
       let guard = self.0.read();
-      guard.games.get(&id).cloned()
+      guard
+        .games
+        .get(&game_ref.id)
+        .filter(|slot| slot.generation == game_ref.generation)
+        .map(|slot| slot.state.clone())
     };
     match state {
       Some(state) => {
         let guard = state.lock_owned().await;
         if guard.closed {
           let mut storage_guard = self.0.write();
-          storage_guard.games.remove(&id);
+          if storage_guard
+            .games
+            .get(&game_ref.id)
+            .map(|slot| slot.generation)
+            == Some(game_ref.generation)
+          {
+            // The slot is about to disappear, so the flush loop's next tick
+            // would otherwise have nothing left to look up by id; carry the
+            // final snapshot forward by value unconditionally. A concurrent
+            // `flush_dirty` may have already drained `dirty` (and be about
+            // to look `game_ref.id` up in `games`) before this runs, so
+            // gating on `dirty.remove` winning that race can drop the
+            // snapshot entirely instead of just double-flushing it.
+            storage_guard.dirty.remove(&game_ref.id);
+            storage_guard
+              .pending_flush
+              .push((game_ref.id, guard.players.clone(), guard.closed));
+            storage_guard.games.remove(&game_ref.id);
+          }
           None
         } else {
           Some(LockedGameState {
-            id,
+            game_ref,
             guard,
             storage_state: self.0.clone(),
           })
@@ -148,50 +1214,225 @@ impl StorageHandle {
     }
   }
 
-  pub fn fetch_num_players(&self, games: &mut [GameEntry]) {
+  pub async fn fetch_num_players(&self, games: &mut [GameEntry]) -> Result<()> {
     for game in games {
-      let state = self.0.read();
-      if let Some(num) = state.game_num_players.get(&game.id).cloned() {
-        game.num_players = num as i32;
+      let remote_owner = {
+        let s = self.0.read();
+        if s.owns(game.id) {
+          None
+        } else {
+          s.cluster.as_ref().and_then(|c| c.owner_of(game.id)).map(|owner| {
+            s.cluster
+              .as_ref()
+              .and_then(|c| c.address_of(owner))
+              .map(|addr| (addr, s.http.clone()))
+          })
+        }
+      };
+
+      let remote_owner = match remote_owner {
+        // Same sticky-assignment-outlived-the-node case as `lock_game_state`:
+        // don't fall through to reading (and leaving stale) the local
+        // `game_num_players` entry for a game this node doesn't own.
+        Some(None) => {
+          return Err(anyhow::anyhow!(
+            "game {} is owned by a node with no known address",
+            game.id
+          ));
+        }
+        Some(Some(target)) => Some(target),
+        None => None,
+      };
+
+      match remote_owner {
+        Some((addr, http)) => {
+          let url = format!("{}/cluster/games/{}/num_players", addr.http_addr, game.id);
+          match http.get(&url).send().await {
+            Ok(resp) if resp.status() == reqwest::StatusCode::NOT_FOUND => {
+              tracing::warn!("remote game {} not found on owning node", game.id);
+            }
+            Ok(resp) => match resp.error_for_status() {
+              Ok(resp) => match resp.json::<i32>().await {
+                Ok(num) => game.num_players = num,
+                Err(err) => tracing::warn!("parse num_players for remote game {}: {}", game.id, err),
+              },
+              Err(err) => tracing::warn!("fetch num_players for remote game {}: {}", game.id, err),
+            },
+            Err(err) => tracing::warn!("fetch num_players for remote game {}: {}", game.id, err),
+          }
+        }
+        None => {
+          let state = self.0.read();
+          if let Some(num) = state.game_num_players.get(&game.id).cloned() {
+            game.num_players = num as i32;
+          }
+        }
       }
     }
+    Ok(())
   }
 }
 
+/// A locked game that may live on this node or be owned by a remote one.
+/// Mutating a remote game round-trips through the cluster RPC endpoint on
+/// every call; the local variant is a direct, lock-free-at-the-call-site
+/// mutation of the in-process `GameState`.
 #[derive(Debug)]
-pub struct LockedPlayerState {
-  id: i32,
-  guard: OwnedMutexGuard<PlayerState>,
+pub enum GameHandle {
+  Local(LockedGameState),
+  Remote(RemoteGameHandle),
 }
 
-impl LockedPlayerState {
+impl GameHandle {
   pub fn id(&self) -> i32 {
-    self.id
+    match self {
+      GameHandle::Local(g) => g.id(),
+      GameHandle::Remote(g) => g.game_ref.id,
+    }
   }
 
-  pub fn joined_game_id(&self) -> Option<i32> {
-    self.guard.game_id.clone()
+  pub fn players(&self) -> &[i32] {
+    match self {
+      GameHandle::Local(g) => g.players(),
+      GameHandle::Remote(g) => &g.players,
+    }
   }
 
-  pub fn join_game(&mut self, game_id: i32) {
-    self.guard.game_id = Some(game_id)
+  pub fn has_player(&self, player_id: i32) -> bool {
+    self.players().contains(&player_id)
   }
 
-  pub fn leave_game(&mut self) {
-    self.guard.game_id = None;
+  pub async fn add_player(&mut self, player_id: i32) -> Result<()> {
+    match self {
+      GameHandle::Local(g) => {
+        g.add_player(player_id);
+        Ok(())
+      }
+      GameHandle::Remote(g) => g.mutate(RemoteGameMutation::AddPlayer { player_id }).await,
+    }
+  }
+
+  pub async fn remove_player(&mut self, player_id: i32) -> Result<()> {
+    match self {
+      GameHandle::Local(g) => {
+        g.remove_player(player_id);
+        Ok(())
+      }
+      GameHandle::Remote(g) => g.mutate(RemoteGameMutation::RemovePlayer { player_id }).await,
+    }
+  }
+
+  pub async fn close(&mut self) -> Result<()> {
+    match self {
+      GameHandle::Local(g) => {
+        g.close();
+        Ok(())
+      }
+      GameHandle::Remote(g) => g.mutate(RemoteGameMutation::Close).await,
+    }
   }
 }
 
+/// A proxy for a game owned by another node. Every mutation is forwarded to
+/// that node's cluster RPC endpoint and the roster snapshot is refreshed
+/// from the response.
 #[derive(Debug)]
-pub struct LockedGameState {
+pub struct RemoteGameHandle {
+  game_ref: GameRef,
+  owner: NodeAddress,
+  http: reqwest::Client,
+  players: Vec<i32>,
+}
+
+impl RemoteGameHandle {
+  async fn mutate(&mut self, mutation: RemoteGameMutation) -> Result<()> {
+    let url = format!(
+      "{}/cluster/games/{}/mutate?generation={}",
+      self.owner.http_addr, self.game_ref.id, self.game_ref.generation
+    );
+    let remote: RemoteGameState = self
+      .http
+      .post(&url)
+      .json(&mutation)
+      .send()
+      .await?
+      .error_for_status()?
+      .json()
+      .await?;
+    self.players = remote.players;
+    Ok(())
+  }
+}
+
+/// Cluster RPC handlers. These are mounted by the node's HTTP server
+/// alongside its other routes, so a remote node can read or mutate a game
+/// this node owns without reaching into its process. The lock and mutate
+/// handlers take the `GameRef` the requester holds so staleness is caught on
+/// the owning node too, not just re-derived from trusting a bare id; the
+/// register and num_players handlers take a bare id.
+pub async fn handle_remote_lock(
+  handle: &StorageHandle,
+  game_ref: GameRef,
+) -> Option<RemoteGameState> {
+  let locked = handle.lock_local_game_state(game_ref).await?;
+  Some(RemoteGameState {
+    players: locked.players().to_vec(),
+    generation: game_ref.generation,
+  })
+}
+
+pub async fn handle_remote_mutation(
+  handle: &StorageHandle,
+  game_ref: GameRef,
+  mutation: RemoteGameMutation,
+) -> Option<RemoteGameState> {
+  let mut locked = handle.lock_local_game_state(game_ref).await?;
+  match mutation {
+    RemoteGameMutation::AddPlayer { player_id } => locked.add_player(player_id),
+    RemoteGameMutation::RemovePlayer { player_id } => locked.remove_player(player_id),
+    RemoteGameMutation::Close => locked.close(),
+  }
+  Some(RemoteGameState {
+    players: locked.players().to_vec(),
+    generation: game_ref.generation,
+  })
+}
+
+pub async fn handle_remote_register(
+  handle: &StorageHandle,
   id: i32,
+  state: RemoteGameState,
+) -> Result<RemoteGameState> {
+  let game_ref = handle.register_game(id, &state.players).await?;
+  Ok(RemoteGameState {
+    players: state.players,
+    generation: game_ref.generation,
+  })
+}
+
+pub async fn handle_remote_num_players(handle: &StorageHandle, id: i32) -> Result<Option<i32>> {
+  let mut entries = [GameEntry {
+    id,
+    num_players: 0,
+  }];
+  handle.fetch_num_players(&mut entries).await?;
+  Ok(Some(entries[0].num_players))
+}
+
+#[derive(Debug)]
+pub struct LockedGameState {
+  game_ref: GameRef,
   guard: OwnedMutexGuard<GameState>,
   storage_state: Arc<RwLock<StorageState>>,
 }
 
 impl LockedGameState {
   pub fn id(&self) -> i32 {
-    self.id
+    self.game_ref.id
+  }
+
+  pub fn game_ref(&self) -> GameRef {
+    self.game_ref
   }
 
   pub fn players(&self) -> &[i32] {
@@ -203,28 +1444,222 @@ impl LockedGameState {
   }
 
   pub fn add_player(&mut self, player_id: i32) {
+    self.guard.last_activity = Instant::now();
     if !self.guard.players.contains(&player_id) {
       self.guard.players.push(player_id);
-      {
-        let mut s = self.storage_state.write();
-        s.game_num_players
-          .entry(self.id)
-          .and_modify(|v| *v = *v + 1);
-      }
+      let mut s = self.storage_state.write();
+      s.game_num_players
+        .entry(self.game_ref.id)
+        .and_modify(|v| *v = *v + 1);
+      let count = s.game_num_players.get(&self.game_ref.id).cloned().unwrap_or(0);
+      s.metrics.set_game_players(self.game_ref.id, count);
+      s.dirty.insert(self.game_ref.id);
     }
   }
 
   pub fn remove_player(&mut self, player_id: i32) {
-    self.guard.players.retain(|id| *id != player_id);
-    {
+    self.guard.last_activity = Instant::now();
+    if self.guard.players.contains(&player_id) {
+      self.guard.players.retain(|id| *id != player_id);
       let mut s = self.storage_state.write();
       s.game_num_players
-        .entry(self.id)
+        .entry(self.game_ref.id)
         .and_modify(|v| *v = *v - 1);
+      let count = s.game_num_players.get(&self.game_ref.id).cloned().unwrap_or(0);
+      s.metrics.set_game_players(self.game_ref.id, count);
+      s.dirty.insert(self.game_ref.id);
     }
   }
 
   pub fn close(&mut self) {
     self.guard.closed = true;
+    let mut s = self.storage_state.write();
+    s.game_num_players.remove(&self.game_ref.id);
+    s.dirty.insert(self.game_ref.id);
+    s.metrics.game_closed(self.game_ref.id);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn test_storage() -> StorageHandle {
+    let registry = Registry::new();
+    let metrics = StorageMetrics::register(&registry).unwrap();
+    StorageHandle(Arc::new(RwLock::new(StorageState::new(
+      Vec::new(),
+      metrics,
+      None,
+    ))))
+  }
+
+  #[tokio::test]
+  async fn repeated_mutations_before_flush_coalesce_into_one_dirty_entry() {
+    let handle = test_storage();
+    let game_ref = handle.register_game(2, &[1]).await.unwrap();
+
+    let mut locked = handle.lock_game_state(game_ref).await.unwrap().unwrap();
+    locked.add_player(2).await.unwrap();
+    locked.add_player(3).await.unwrap();
+    drop(locked);
+
+    let state = handle.0.read();
+    assert_eq!(state.dirty.iter().filter(|id| **id == game_ref.id).count(), 1);
+  }
+
+  #[tokio::test]
+  async fn closing_a_game_survives_slot_eviction_for_flush() {
+    let handle = test_storage();
+    let game_ref = handle.register_game(1, &[7, 8]).await.unwrap();
+
+    let mut locked = handle.lock_game_state(game_ref).await.unwrap().unwrap();
+    locked.close().await.unwrap();
+    drop(locked);
+
+    // A straggling caller (e.g. another player in the same game) is the one
+    // that actually evicts the slot; the flush loop must still see the
+    // game's final state afterwards.
+    assert!(handle.lock_local_game_state(game_ref).await.is_none());
+
+    let state = handle.0.read();
+    assert!(state.games.get(&game_ref.id).is_none());
+    assert_eq!(state.pending_flush, vec![(game_ref.id, vec![7, 8], true)]);
+    assert!(!state.dirty.contains(&game_ref.id));
+  }
+
+  #[tokio::test]
+  async fn remote_rpc_handlers_forward_register_lock_and_mutate() {
+    // Exercises the server-side handlers a remote node's requests land on,
+    // as a stand-in for the owning node in a real cluster RPC round trip.
+    let owner = test_storage();
+
+    let registered = handle_remote_register(
+      &owner,
+      1,
+      RemoteGameState {
+        players: vec![1, 2],
+        generation: 0,
+      },
+    )
+    .await
+    .unwrap();
+    let game_ref = GameRef {
+      id: 1,
+      generation: registered.generation,
+    };
+
+    let locked = handle_remote_lock(&owner, game_ref).await.unwrap();
+    assert_eq!(locked.players, vec![1, 2]);
+
+    let mutation = RemoteGameMutation::AddPlayer { player_id: 3 };
+    let mutated = handle_remote_mutation(&owner, game_ref, mutation)
+      .await
+      .unwrap();
+    assert_eq!(mutated.players, vec![1, 2, 3]);
+
+    let stale_ref = GameRef {
+      id: 1,
+      generation: game_ref.generation + 1,
+    };
+    assert!(handle_remote_lock(&owner, stale_ref).await.is_none());
+  }
+
+  #[tokio::test]
+  async fn janitor_closes_idle_games_and_reaps_senderless_players() {
+    let handle = test_storage();
+    let game_ref = handle.register_game(9, &[1]).await.unwrap();
+    let player_id = 2;
+    // Spawns the player actor with no game and no sender.
+    assert_eq!(handle.player_game_id(player_id).await, None);
+
+    let config = StorageConfig {
+      max_game_inactivity: Duration::from_millis(1),
+      max_client_inactivity: Duration::from_millis(1),
+      ..StorageConfig::default()
+    };
+    tokio::time::sleep(Duration::from_millis(5)).await;
+
+    reap_inactive(&handle.0, &config).await;
+
+    // The game is marked closed and dirtied; the slot itself is evicted the
+    // next time it's locked (see `closing_a_game_survives_slot_eviction_for_flush`).
+    assert!(handle.lock_game_state(game_ref).await.unwrap().is_none());
+    {
+      let s = handle.0.read();
+      assert!(s.players.get(&player_id).is_none());
+      assert_eq!(s.metrics.active_players.get(), 0);
+    }
+  }
+
+  #[tokio::test]
+  async fn metrics_track_games_and_players_through_their_lifecycle() {
+    let handle = test_storage();
+
+    let game_ref = handle.register_game(10, &[1, 2]).await.unwrap();
+    {
+      let s = handle.0.read();
+      assert_eq!(s.metrics.active_games.get(), 1);
+      assert_eq!(s.metrics.active_players.get(), 2);
+      assert_eq!(s.metrics.game_players.with_label_values(&["10"]).get(), 2);
+    }
+
+    let mut locked = handle.lock_game_state(game_ref).await.unwrap().unwrap();
+    locked.add_player(3).await.unwrap();
+    drop(locked);
+    {
+      let s = handle.0.read();
+      assert_eq!(s.metrics.game_players.with_label_values(&["10"]).get(), 3);
+    }
+
+    let mut locked = handle.lock_game_state(game_ref).await.unwrap().unwrap();
+    locked.remove_player(3).await.unwrap();
+    locked.close().await.unwrap();
+    drop(locked);
+    {
+      let s = handle.0.read();
+      assert_eq!(s.metrics.active_games.get(), 0);
+      assert_eq!(s.metrics.games_closed_total.get(), 1);
+    }
+  }
+
+  #[tokio::test]
+  async fn player_mailbox_round_trips_join_leave_sender_and_query() {
+    let handle = test_storage();
+    let player_id = 42;
+
+    assert_eq!(handle.player_game_id(player_id).await, None);
+
+    handle.join_game(player_id, 7).await;
+    assert_eq!(handle.player_game_id(player_id).await, Some(7));
+
+    handle.leave_game(player_id).await;
+    assert_eq!(handle.player_game_id(player_id).await, None);
+
+    let (_player_ref, player_handle) = handle.player_ref(player_id);
+    let status = player_handle.status().await.unwrap();
+    assert!(!status.has_sender);
+
+    handle.set_player_sender(player_id, None).await;
+    let status = player_handle.status().await.unwrap();
+    assert!(!status.has_sender);
+  }
+
+  #[tokio::test]
+  async fn switch_game_atomically_reports_and_replaces_the_previous_game() {
+    let handle = test_storage();
+    let player_id = 7;
+
+    // No prior assignment to report.
+    assert_eq!(handle.switch_game(player_id, Some(1)).await, None);
+    assert_eq!(handle.player_game_id(player_id).await, Some(1));
+
+    // Reports the game being left while already seated in the new one, in
+    // one mailbox round trip rather than a separate read then write.
+    assert_eq!(handle.switch_game(player_id, Some(2)).await, Some(1));
+    assert_eq!(handle.player_game_id(player_id).await, Some(2));
+
+    assert_eq!(handle.switch_game(player_id, None).await, Some(2));
+    assert_eq!(handle.player_game_id(player_id).await, None);
   }
 }